@@ -4,6 +4,7 @@ use crate::topstack::get_dispatchers;
 use bitflags::bitflags;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::cast::FromPrimitive;
+use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::sync::{Arc, Mutex};
 use topshim_macros::{cb_variant, profile_enabled_or};
@@ -76,6 +77,16 @@ impl From<u32> for BthfAudioState {
     }
 }
 
+// eVRA state sent to an enhanced-voice-recognition capable HF alongside the plain on/off
+// AT+BVRA toggle. See HFP v1.9 Sec 4.34.2.
+#[derive(Debug, FromPrimitive, ToPrimitive, PartialEq, PartialOrd, Clone)]
+#[repr(u8)]
+pub enum HfpVoiceRecognitionState {
+    Ready = 0,
+    Sound = 1,
+    Processing = 2,
+}
+
 // This is used for codec-negotiation related methods that do not
 // concern with the coding format. Do not confuse this with |HfpCodecFormat|.
 bitflags! {
@@ -134,6 +145,82 @@ impl TryFrom<i32> for HfpCodecFormat {
     }
 }
 
+// AG-side supported-features bitmask exchanged in +BRSF at SLC setup. See HFP v1.9 Sec 4.34.1.
+bitflags! {
+    #[derive(Default)]
+    pub struct HfpAgFeatures: i32 {
+        const NONE                       = 0b0000_0000_0000_0;
+        const THREE_WAY_CALLING          = 0b0000_0000_0000_1;
+        const EC_NR                      = 0b0000_0000_0001_0;
+        const VOICE_RECOGNITION          = 0b0000_0000_0010_0;
+        const IN_BAND_RING_TONE          = 0b0000_0000_0100_0;
+        const ATTACH_NUMBER_TO_VOICE_TAG = 0b0000_0000_1000_0;
+        const ENHANCED_CALL_STATUS       = 0b0000_0001_0000_0;
+        const ENHANCED_CALL_CONTROL      = 0b0000_0010_0000_0;
+        const EXTENDED_ERROR_CODES       = 0b0000_0100_0000_0;
+        const CODEC_NEGOTIATION          = 0b0000_1000_0000_0;
+        const HF_INDICATORS              = 0b0001_0000_0000_0;
+        const ESCO_S4_SETTINGS           = 0b0010_0000_0000_0;
+        const ENHANCED_VOICE_RECOGNITION = 0b0100_0000_0000_0;
+        const VOICE_RECOGNITION_TEXT     = 0b1000_0000_0000_0;
+    }
+}
+
+impl TryInto<i32> for HfpAgFeatures {
+    type Error = ();
+    fn try_into(self) -> Result<i32, Self::Error> {
+        Ok(self.bits())
+    }
+}
+
+impl TryFrom<i32> for HfpAgFeatures {
+    type Error = ();
+    fn try_from(val: i32) -> Result<Self, Self::Error> {
+        Self::from_bits(val).ok_or(())
+    }
+}
+
+// HF-side counterpart of |HfpAgFeatures|, reported by the remote in its own +BRSF request.
+bitflags! {
+    #[derive(Default)]
+    pub struct HfpHfFeatures: i32 {
+        const NONE                       = 0b0000_0000_0000_0;
+        const THREE_WAY_CALLING          = 0b0000_0000_0000_1;
+        const EC_NR                      = 0b0000_0000_0001_0;
+        const VOICE_RECOGNITION          = 0b0000_0000_0010_0;
+        const IN_BAND_RING_TONE          = 0b0000_0000_0100_0;
+        const ATTACH_NUMBER_TO_VOICE_TAG = 0b0000_0000_1000_0;
+        const ENHANCED_CALL_STATUS       = 0b0000_0001_0000_0;
+        const ENHANCED_CALL_CONTROL      = 0b0000_0010_0000_0;
+        const EXTENDED_ERROR_CODES       = 0b0000_0100_0000_0;
+        const CODEC_NEGOTIATION          = 0b0000_1000_0000_0;
+        const HF_INDICATORS              = 0b0001_0000_0000_0;
+        const ESCO_S4_SETTINGS           = 0b0010_0000_0000_0;
+        const ENHANCED_VOICE_RECOGNITION = 0b0100_0000_0000_0;
+        const VOICE_RECOGNITION_TEXT     = 0b1000_0000_0000_0;
+    }
+}
+
+impl TryInto<i32> for HfpHfFeatures {
+    type Error = ();
+    fn try_into(self) -> Result<i32, Self::Error> {
+        Ok(self.bits())
+    }
+}
+
+impl TryFrom<i32> for HfpHfFeatures {
+    type Error = ();
+    fn try_from(val: i32) -> Result<Self, Self::Error> {
+        Self::from_bits(val).ok_or(())
+    }
+}
+
+impl From<i32> for HfpHfFeatures {
+    fn from(val: i32) -> Self {
+        Self::from_bits_truncate(val)
+    }
+}
+
 #[cxx::bridge(namespace = bluetooth::topshim::rust)]
 pub mod ffi {
     unsafe extern "C++" {
@@ -227,7 +314,32 @@ pub mod ffi {
             addr: RawAddress,
         ) -> u32;
         fn simple_at_response(self: Pin<&mut HfpIntf>, ok: bool, addr: RawAddress) -> u32;
+        fn send_at_result(
+            self: Pin<&mut HfpIntf>,
+            code: String,
+            is_final: bool,
+            addr: RawAddress,
+        ) -> u32;
+        fn send_unsolicited_result(
+            self: Pin<&mut HfpIntf>,
+            code: String,
+            addr: RawAddress,
+        ) -> u32;
         fn debug_dump(self: Pin<&mut HfpIntf>);
+        fn set_ag_features(self: Pin<&mut HfpIntf>, features: i32) -> u32;
+        fn set_voice_recognition(self: Pin<&mut HfpIntf>, enabled: bool, addr: RawAddress) -> u32;
+        fn send_bvra_response(
+            self: Pin<&mut HfpIntf>,
+            state: u8,
+            text: String,
+            addr: RawAddress,
+        ) -> u32;
+        fn send_apple_accessory_features(
+            self: Pin<&mut HfpIntf>,
+            battery_reporting: bool,
+            docked_reporting: bool,
+            addr: RawAddress,
+        ) -> u32;
         fn cleanup(self: Pin<&mut HfpIntf>);
 
     }
@@ -237,6 +349,9 @@ pub mod ffi {
         fn hfp_volume_update_callback(volume: u8, addr: RawAddress);
         fn hfp_mic_volume_update_callback(volume: u8, addr: RawAddress);
         fn hfp_vendor_specific_at_command_callback(at_string: String, addr: RawAddress);
+        fn hfp_unknown_at_command_callback(at_string: String, addr: RawAddress);
+        fn hfp_hf_features_callback(features: i32, addr: RawAddress);
+        fn hfp_voice_recognition_state_callback(enabled: bool, addr: RawAddress);
         fn hfp_battery_level_update_callback(battery_level: u8, addr: RawAddress);
         fn hfp_wbs_caps_update_callback(wbs_supported: bool, addr: RawAddress);
         fn hfp_swb_caps_update_callback(swb_supported: bool, addr: RawAddress);
@@ -282,6 +397,81 @@ pub type CallInfo = ffi::CallInfo;
 pub type PhoneState = ffi::PhoneState;
 pub type CallHoldCommand = ffi::CallHoldCommand;
 
+// Structured accessory state parsed out of the Apple (+IPHONEACCEV/+XAPL) and Android (+ANDROID)
+// vendor AT commands, superseding the single lossy |HfpCallbacks::BatteryLevelUpdate| byte for
+// accessories that advertise richer state via these extensions.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct HfpAccessoryState {
+    pub battery_level_percent: Option<u8>,
+    pub docked: Option<bool>,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+}
+
+// Parses the +IPHONEACCEV key/value report. Key 1 is battery level on a 0-9 scale, key 2 is dock
+// state. See Apple's "Accessory Design Guidelines for Apple Devices", HFP vendor extensions.
+fn parse_iphoneaccev(params: &str) -> Option<HfpAccessoryState> {
+    let mut fields = params.split(',').map(str::trim);
+    let count: usize = fields.next()?.parse().ok()?;
+    let mut state = HfpAccessoryState::default();
+    for _ in 0..count {
+        let key: u8 = fields.next()?.parse().ok()?;
+        let value: u8 = fields.next()?.parse().ok()?;
+        match key {
+            1 => state.battery_level_percent = Some((value as u32 * 100 / 9) as u8),
+            2 => state.docked = Some(value != 0),
+            _ => (),
+        }
+    }
+    Some(state)
+}
+
+// Parses the vendor/product identifiers out of the HF's AT+XAPL=<vendorID>-<productID>-<version>,
+// <features> handshake.
+fn parse_xapl(params: &str) -> Option<HfpAccessoryState> {
+    let mut ids = params.split(',').next()?.splitn(3, '-').map(str::trim);
+    let vendor_id = u16::from_str_radix(ids.next()?, 16).ok()?;
+    let product_id = u16::from_str_radix(ids.next()?, 16).ok()?;
+    Some(HfpAccessoryState {
+        vendor_id: Some(vendor_id),
+        product_id: Some(product_id),
+        ..Default::default()
+    })
+}
+
+// Parses the Android accessory vendor command, e.g. "+ANDROID=BATTERY,<percent>". Unlike
+// +IPHONEACCEV's 0-9 scale, the percent here comes straight off the wire with no inherent bound,
+// so reject anything outside 0-100 rather than surface a bogus reading to the UI.
+fn parse_android_accessory(params: &str) -> Option<HfpAccessoryState> {
+    let mut fields = params.splitn(2, ',').map(str::trim);
+    if fields.next()? != "BATTERY" {
+        return None;
+    }
+    let percent: u8 = fields.next()?.parse().ok()?;
+    if percent > 100 {
+        return None;
+    }
+    Some(HfpAccessoryState { battery_level_percent: Some(percent), ..Default::default() })
+}
+
+// Attempts to parse a raw AT string surfaced via |HfpCallbacks::VendorSpecificAtCommand| or
+// |HfpCallbacks::UnknownAtCommand| as one of the Apple/Android accessory indicator extensions.
+fn parse_accessory_state(at_command: &str) -> Option<HfpAccessoryState> {
+    let command = at_command.trim();
+    if let Some(params) =
+        command.strip_prefix("+IPHONEACCEV:").or_else(|| command.strip_prefix("+IPHONEACCEV="))
+    {
+        return parse_iphoneaccev(params);
+    }
+    if let Some(params) = command.strip_prefix("+XAPL=") {
+        return parse_xapl(params);
+    }
+    if let Some(params) = command.strip_prefix("+ANDROID=") {
+        return parse_android_accessory(params);
+    }
+    None
+}
+
 #[derive(Clone, Debug)]
 pub enum HfpCallbacks {
     ConnectionState(BthfConnectionState, RawAddress),
@@ -289,6 +479,17 @@ pub enum HfpCallbacks {
     VolumeUpdate(u8, RawAddress),
     MicVolumeUpdate(u8, RawAddress),
     VendorSpecificAtCommand(String, RawAddress),
+    // Parsed out of +IPHONEACCEV/+XAPL/+ANDROID vendor AT commands surfaced via
+    // |VendorSpecificAtCommand|/|UnknownAtCommand|. See |parse_accessory_state|.
+    AccessoryState(HfpAccessoryState, RawAddress),
+    // Raised for AT commands that none of the fixed AG responders above handle, so an
+    // out-of-process telephony provider (e.g. oFono/BlueZ org.bluez.Telephony-style agent) can
+    // answer them via |Hfp::send_at_result|/|Hfp::send_unsolicited_result|.
+    UnknownAtCommand(String, RawAddress),
+    // Delivered when the remote's AT+BRSF arrives during SLC setup, reporting the HF feature mask.
+    HfFeatures(HfpHfFeatures, RawAddress),
+    // Raised when the HF initiates or terminates voice recognition via AT+BVRA.
+    VoiceRecognitionState(bool, RawAddress),
     BatteryLevelUpdate(u8, RawAddress),
     WbsCapsUpdate(bool, RawAddress),
     SwbCapsUpdate(bool, RawAddress),
@@ -299,12 +500,22 @@ pub enum HfpCallbacks {
     DialCall(String, RawAddress),
     CallHold(CallHoldCommand, RawAddress),
     DebugDump(bool, u16, i32, f64, u64, u64, String, String),
+    // Synthesized locally (not an AT event) when the codec fallback controller tears down a noisy
+    // wideband link and re-establishes it on the fallback codec. See |Hfp::set_codec_fallback_policy|.
+    CodecFallback(HfpCodecId, RawAddress),
 }
 
 pub struct HfpCallbacksDispatcher {
     pub dispatch: Box<dyn Fn(HfpCallbacks) + Send>,
 }
 
+// Registered per-device by an external telephony provider that wants to own
+// |HfpCallbacks::UnknownAtCommand| for that device and answer it via |Hfp::send_at_result|/
+// |Hfp::send_unsolicited_result|. See |Hfp::register_telephony_provider|.
+pub struct HfpTelephonyProvider {
+    pub dispatch: Box<dyn Fn(String) + Send>,
+}
+
 type HfpCb = Arc<Mutex<HfpCallbacksDispatcher>>;
 
 cb_variant!(
@@ -332,6 +543,21 @@ cb_variant!(
     hfp_vendor_specific_at_command_callback -> HfpCallbacks::VendorSpecificAtCommand,
     String, RawAddress);
 
+cb_variant!(
+    HfpCb,
+    hfp_unknown_at_command_callback -> HfpCallbacks::UnknownAtCommand,
+    String, RawAddress);
+
+cb_variant!(
+    HfpCb,
+    hfp_hf_features_callback -> HfpCallbacks::HfFeatures,
+    i32 -> HfpHfFeatures, RawAddress);
+
+cb_variant!(
+    HfpCb,
+    hfp_voice_recognition_state_callback -> HfpCallbacks::VoiceRecognitionState,
+    bool, RawAddress);
+
 cb_variant!(
     HfpCb,
     hfp_battery_level_update_callback -> HfpCallbacks::BatteryLevelUpdate,
@@ -382,10 +608,182 @@ cb_variant!(
     hfp_debug_dump_callback -> HfpCallbacks::DebugDump,
     bool, u16, i32, f64, u64, u64, String, String);
 
+// Tunables for the adaptive SCO codec fallback controller. See |Hfp::set_codec_fallback_policy|.
+#[derive(Debug, Clone, Copy)]
+pub struct HfpCodecFallbackPolicy {
+    // Downgrade once the packet-loss EWMA stays above this ratio for a whole |window| of dumps.
+    pub pkt_loss_ratio_threshold: f64,
+    pub window: usize,
+    // How long to stay on the fallback codec before giving the preferred codec another chance.
+    pub cooldown: std::time::Duration,
+}
+
+impl Default for HfpCodecFallbackPolicy {
+    fn default() -> Self {
+        HfpCodecFallbackPolicy {
+            pkt_loss_ratio_threshold: 0.15,
+            window: 10,
+            cooldown: std::time::Duration::from_secs(30),
+        }
+    }
+}
+
+struct CodecFallbackLinkState {
+    ewma: f64,
+    consecutive_samples_over_threshold: usize,
+}
+
+impl CodecFallbackLinkState {
+    fn new() -> Self {
+        CodecFallbackLinkState { ewma: 0.0, consecutive_samples_over_threshold: 0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CodecFallbackAction {
+    // Tear down and re-establish audio with |disabled_codecs| masking out the offending codec.
+    Downgrade(i32),
+    // Cooldown elapsed on a previously-downgraded link; re-attempt the preferred codec.
+    RetryPreferred,
+}
+
+// Consumes |HfpCallbacks::DebugDump|/|HfpCallbacks::AudioState| events for the active SCO link and
+// decides when a noisy wideband (mSBC/LC3) codec should be abandoned in favor of CVSD.
+//
+// This is driven entirely from data (no FFI calls), so it's safe to update from the callback
+// dispatch closure, which runs on a thread that only the owning |Hfp| (via its real, non-Send FFI
+// handle) may touch. The actual disconnect_audio/connect_audio round-trip is performed by
+// |Hfp::drive_codec_fallback|, which runs on the caller's thread and drains the queues below; it
+// waits for the disconnect to actually land (observed via |on_audio_state|) before reconnecting,
+// since SCO teardown is asynchronous on real hardware.
+struct CodecFallbackController {
+    policy: HfpCodecFallbackPolicy,
+    active_addr: Option<RawAddress>,
+    links: HashMap<RawAddress, CodecFallbackLinkState>,
+    // When a link has been downgraded to the fallback codec, how long to stay on it before giving
+    // the preferred codec another chance. Tracked independent of |links|, since the disconnect this
+    // controller itself requests to enact the downgrade always lands a real
+    // |on_audio_state(Disconnected, addr)|, which clears |links| -- this timer must survive that.
+    cooldowns: HashMap<RawAddress, std::time::Instant>,
+    // Disabled-codecs mask to reconnect with once the remote address below that we asked to
+    // disconnect actually reports |BthfAudioState::Disconnected|.
+    awaiting_disconnect: HashMap<RawAddress, i32>,
+    pending_disconnects: Vec<RawAddress>,
+    pending_reconnects: Vec<(RawAddress, i32)>,
+}
+
+impl CodecFallbackController {
+    fn new() -> Self {
+        CodecFallbackController {
+            policy: HfpCodecFallbackPolicy::default(),
+            active_addr: None,
+            links: HashMap::new(),
+            cooldowns: HashMap::new(),
+            awaiting_disconnect: HashMap::new(),
+            pending_disconnects: Vec::new(),
+            pending_reconnects: Vec::new(),
+        }
+    }
+
+    fn set_policy(&mut self, policy: HfpCodecFallbackPolicy) {
+        self.policy = policy;
+    }
+
+    fn on_audio_state(&mut self, state: BthfAudioState, addr: RawAddress) {
+        match state {
+            BthfAudioState::Connected => self.active_addr = Some(addr),
+            BthfAudioState::Disconnected => {
+                if self.active_addr == Some(addr) {
+                    self.active_addr = None;
+                }
+                if let Some(disabled_codecs) = self.awaiting_disconnect.remove(&addr) {
+                    self.pending_reconnects.push((addr, disabled_codecs));
+                }
+                // A fresh connection starts clean and gets another shot at the preferred codec.
+                self.links.remove(&addr);
+            }
+            _ => (),
+        }
+    }
+
+    // Evaluates one debug dump and, if it decides a downgrade or a preferred-codec retry is due,
+    // queues the disconnect for |Hfp::drive_codec_fallback| and remembers the disabled-codecs mask
+    // to use once the resulting |on_audio_state(Disconnected, addr)| arrives. Returns the decided
+    // action so the caller can additionally surface |HfpCallbacks::CodecFallback|.
+    fn on_debug_dump(
+        &mut self,
+        active: bool,
+        codec_id: u16,
+        pkt_loss_ratio: f64,
+    ) -> Option<(RawAddress, CodecFallbackAction)> {
+        if !active {
+            return None;
+        }
+        let addr = self.active_addr?;
+        let is_wideband =
+            codec_id == HfpCodecId::MSBC as u16 || codec_id == HfpCodecId::LC3 as u16;
+
+        if let Some(cooldown_until) = self.cooldowns.get(&addr).copied() {
+            if std::time::Instant::now() < cooldown_until {
+                return None;
+            }
+            self.cooldowns.remove(&addr);
+            self.links.remove(&addr);
+            self.pending_disconnects.push(addr);
+            self.awaiting_disconnect.insert(addr, HfpCodecBitId::NONE.bits());
+            return Some((addr, CodecFallbackAction::RetryPreferred));
+        }
+
+        if !is_wideband {
+            return None;
+        }
+
+        let link = self.links.entry(addr).or_insert_with(CodecFallbackLinkState::new);
+        link.ewma = 0.75 * link.ewma + 0.25 * pkt_loss_ratio;
+        if link.ewma > self.policy.pkt_loss_ratio_threshold {
+            link.consecutive_samples_over_threshold += 1;
+        } else {
+            link.consecutive_samples_over_threshold = 0;
+        }
+
+        if link.consecutive_samples_over_threshold < self.policy.window {
+            return None;
+        }
+
+        self.cooldowns.insert(addr, std::time::Instant::now() + self.policy.cooldown);
+        let disabled_codecs = if codec_id == HfpCodecId::MSBC as u16 {
+            HfpCodecBitId::MSBC.bits()
+        } else {
+            HfpCodecBitId::LC3.bits()
+        };
+        self.pending_disconnects.push(addr);
+        self.awaiting_disconnect.insert(addr, disabled_codecs);
+        Some((addr, CodecFallbackAction::Downgrade(disabled_codecs)))
+    }
+
+    fn take_pending_disconnects(&mut self) -> Vec<RawAddress> {
+        std::mem::take(&mut self.pending_disconnects)
+    }
+
+    fn take_pending_reconnects(&mut self) -> Vec<(RawAddress, i32)> {
+        std::mem::take(&mut self.pending_reconnects)
+    }
+}
+
 pub struct Hfp {
     internal: cxx::UniquePtr<ffi::HfpIntf>,
     _is_init: bool,
     _is_enabled: bool,
+    hf_features: Arc<Mutex<HashMap<RawAddress, HfpHfFeatures>>>,
+    // Providers registered via |register_telephony_provider| that have taken ownership of
+    // |HfpCallbacks::UnknownAtCommand| for a given device.
+    telephony_providers: Arc<Mutex<HashMap<RawAddress, Arc<HfpTelephonyProvider>>>>,
+    // |ffi::HfpIntf| is an opaque C++ type with no thread-safety guarantee, so it can't be touched
+    // from the callback dispatch closure (which must stay |Send| and may run on a different
+    // thread). The controller itself holds no FFI handle -- only data -- so it's safe to share
+    // with that closure; |drive_codec_fallback| drains its decisions on the caller's thread, where
+    // |internal| is actually accessible.
+    codec_fallback: Arc<Mutex<CodecFallbackController>>,
 }
 
 // For *const u8 opaque btif
@@ -417,7 +815,14 @@ impl Hfp {
             hfpif = ffi::GetHfpProfile(intf.as_raw_ptr());
         }
 
-        Hfp { internal: hfpif, _is_init: false, _is_enabled: false }
+        Hfp {
+            internal: hfpif,
+            _is_init: false,
+            _is_enabled: false,
+            hf_features: Arc::new(Mutex::new(HashMap::new())),
+            telephony_providers: Arc::new(Mutex::new(HashMap::new())),
+            codec_fallback: Arc::new(Mutex::new(CodecFallbackController::new())),
+        }
     }
 
     pub fn is_initialized(&self) -> bool {
@@ -425,13 +830,80 @@ impl Hfp {
     }
 
     pub fn initialize(&mut self, callbacks: HfpCallbacksDispatcher) -> bool {
-        if get_dispatchers().lock().unwrap().set::<HfpCb>(Arc::new(Mutex::new(callbacks))) {
+        let hf_features = self.hf_features.clone();
+        let telephony_providers = self.telephony_providers.clone();
+        let codec_fallback = self.codec_fallback.clone();
+        let wrapped = HfpCallbacksDispatcher {
+            dispatch: Box::new(move |cb| {
+                match &cb {
+                    HfpCallbacks::HfFeatures(features, addr) => {
+                        hf_features.lock().unwrap().insert(*addr, features.clone());
+                    }
+                    HfpCallbacks::AudioState(state, addr) => {
+                        codec_fallback.lock().unwrap().on_audio_state(state.clone(), *addr);
+                    }
+                    HfpCallbacks::VendorSpecificAtCommand(at_string, addr) => {
+                        if let Some(state) = parse_accessory_state(at_string) {
+                            (callbacks.dispatch)(HfpCallbacks::AccessoryState(state, *addr));
+                        }
+                    }
+                    HfpCallbacks::UnknownAtCommand(at_string, addr) => {
+                        if let Some(state) = parse_accessory_state(at_string) {
+                            (callbacks.dispatch)(HfpCallbacks::AccessoryState(state, *addr));
+                        }
+                        let provider = telephony_providers.lock().unwrap().get(addr).cloned();
+                        if let Some(provider) = provider {
+                            (provider.dispatch)(at_string.clone());
+                        }
+                    }
+                    HfpCallbacks::DebugDump(
+                        active,
+                        codec_id,
+                        _total_num_decoded_frames,
+                        pkt_loss_ratio,
+                        _begin_ts,
+                        _end_ts,
+                        _pkt_status_in_hex,
+                        _pkt_status_in_binary,
+                    ) => {
+                        if let Some((addr, CodecFallbackAction::Downgrade(_))) = codec_fallback
+                            .lock()
+                            .unwrap()
+                            .on_debug_dump(*active, *codec_id, *pkt_loss_ratio)
+                        {
+                            (callbacks.dispatch)(HfpCallbacks::CodecFallback(
+                                HfpCodecId::from_u16(*codec_id).unwrap_or(HfpCodecId::NONE),
+                                addr,
+                            ));
+                        }
+                    }
+                    _ => (),
+                }
+                (callbacks.dispatch)(cb);
+            }),
+        };
+        if get_dispatchers().lock().unwrap().set::<HfpCb>(Arc::new(Mutex::new(wrapped))) {
             panic!("Tried to set dispatcher for HFP callbacks while it already exists");
         }
         self._is_init = true;
         true
     }
 
+    // Executes the disconnect_audio/connect_audio round-trips decided by the codec-fallback
+    // controller since the last call, on this (caller's) thread. A disconnect is only followed by
+    // its matching reconnect once the remote has actually reported
+    // |BthfAudioState::Disconnected| for that address, since SCO teardown is asynchronous.
+    // Call this periodically, e.g. alongside |debug_dump|.
+    pub fn drive_codec_fallback(&mut self) {
+        for addr in self.codec_fallback.lock().unwrap().take_pending_disconnects() {
+            self.disconnect_audio(addr);
+        }
+        for (addr, disabled_codecs) in self.codec_fallback.lock().unwrap().take_pending_reconnects()
+        {
+            self.connect_audio(addr, /*sco_offload=*/ false, disabled_codecs);
+        }
+    }
+
     #[profile_enabled_or(BtStatus::NotReady)]
     pub fn connect(&mut self, addr: RawAddress) -> BtStatus {
         BtStatus::from(self.internal.pin_mut().connect(addr))
@@ -519,14 +991,247 @@ impl Hfp {
         BtStatus::from(self.internal.pin_mut().simple_at_response(ok, addr))
     }
 
+    // Registers an external telephony provider (e.g. a vendor-specific AT command handler living
+    // outside this process) as the owner of |HfpCallbacks::UnknownAtCommand| for |addr|. Until a
+    // provider is registered for a device, |send_at_result|/|send_unsolicited_result| refuse to
+    // send anything on its behalf. Returns false if a provider was already registered for |addr|
+    // (and replaces it regardless -- callers that care should check first).
+    pub fn register_telephony_provider(
+        &mut self,
+        addr: RawAddress,
+        provider: HfpTelephonyProvider,
+    ) -> bool {
+        self.telephony_providers.lock().unwrap().insert(addr, Arc::new(provider)).is_none()
+    }
+
+    pub fn unregister_telephony_provider(&mut self, addr: RawAddress) -> bool {
+        self.telephony_providers.lock().unwrap().remove(&addr).is_some()
+    }
+
+    // Allows an external telephony provider to answer a command surfaced via
+    // |HfpCallbacks::UnknownAtCommand| with an arbitrary AG result code, e.g. "+COPS: 0,0,\"Carrier\"",
+    // followed eventually by an OK/ERROR final. Requires a provider to have been registered for
+    // |addr| via |register_telephony_provider|.
+    #[profile_enabled_or(BtStatus::NotReady)]
+    pub fn send_at_result(&mut self, code: String, is_final: bool, addr: RawAddress) -> BtStatus {
+        if !self.telephony_providers.lock().unwrap().contains_key(&addr) {
+            return BtStatus::NotReady;
+        }
+        BtStatus::from(self.internal.pin_mut().send_at_result(code, is_final, addr))
+    }
+
+    // Sends an unsolicited AG result code (e.g. "+CIEV", "+VGS") that was not requested by the HF,
+    // on behalf of the registered telephony provider. Requires a provider to have been registered
+    // for |addr| via |register_telephony_provider|.
+    #[profile_enabled_or(BtStatus::NotReady)]
+    pub fn send_unsolicited_result(&mut self, code: String, addr: RawAddress) -> BtStatus {
+        if !self.telephony_providers.lock().unwrap().contains_key(&addr) {
+            return BtStatus::NotReady;
+        }
+        BtStatus::from(self.internal.pin_mut().send_unsolicited_result(code, addr))
+    }
+
     #[profile_enabled_or]
     pub fn debug_dump(&mut self) {
+        self.drive_codec_fallback();
         self.internal.pin_mut().debug_dump();
     }
 
+    // Configures the adaptive SCO codec fallback controller that watches |debug_dump| output for
+    // the active link and downgrades away from a persistently lossy wideband codec.
+    pub fn set_codec_fallback_policy(&mut self, policy: HfpCodecFallbackPolicy) {
+        self.codec_fallback.lock().unwrap().set_policy(policy);
+    }
+
+    // Sets the AG feature mask advertised in +BRSF at SLC setup, gating in-band ringtone, enhanced
+    // call control, codec negotiation, etc. The one mask applies to every device (not per-device).
+    #[profile_enabled_or(BtStatus::NotReady)]
+    pub fn set_ag_features(&mut self, features: HfpAgFeatures) -> BtStatus {
+        BtStatus::from(self.internal.pin_mut().set_ag_features(features.bits()))
+    }
+
+    // Sends AT+BVRA to start or stop voice recognition on the remote, e.g. to wire a car/headset
+    // "press to talk" button into the platform assistant.
+    #[profile_enabled_or(BtStatus::NotReady)]
+    pub fn set_voice_recognition(&mut self, enabled: bool, addr: RawAddress) -> BtStatus {
+        BtStatus::from(self.internal.pin_mut().set_voice_recognition(enabled, addr))
+    }
+
+    // Carries the eVRA text state (and an optional UTF-8 message to show/speak) to a remote that
+    // advertised |HfpHfFeatures::ENHANCED_VOICE_RECOGNITION| in its +BRSF. No-ops as |BtStatus::NotReady|
+    // for remotes that never reported the bit, since the AG can't assume they'll parse the extended form.
+    #[profile_enabled_or(BtStatus::NotReady)]
+    pub fn send_bvra_response(
+        &mut self,
+        state: HfpVoiceRecognitionState,
+        text: Option<String>,
+        addr: RawAddress,
+    ) -> BtStatus {
+        let supports_evra = self
+            .hf_features
+            .lock()
+            .unwrap()
+            .get(&addr)
+            .map_or(false, |f| f.contains(HfpHfFeatures::ENHANCED_VOICE_RECOGNITION));
+        if !supports_evra {
+            return BtStatus::NotReady;
+        }
+        BtStatus::from(self.internal.pin_mut().send_bvra_response(
+            state as u8,
+            text.unwrap_or_default(),
+            addr,
+        ))
+    }
+
+    // Advertises AT+XAPL battery/dock reporting support to Apple accessories.
+    #[profile_enabled_or(BtStatus::NotReady)]
+    pub fn send_apple_accessory_features(
+        &mut self,
+        battery_reporting: bool,
+        docked_reporting: bool,
+        addr: RawAddress,
+    ) -> BtStatus {
+        BtStatus::from(self.internal.pin_mut().send_apple_accessory_features(
+            battery_reporting,
+            docked_reporting,
+            addr,
+        ))
+    }
+
     #[profile_enabled_or(false)]
     pub fn cleanup(&mut self) -> bool {
         self.internal.pin_mut().cleanup();
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(last_byte: u8) -> RawAddress {
+        RawAddress { val: [0, 0, 0, 0, 0, last_byte] }
+    }
+
+    fn fast_policy() -> HfpCodecFallbackPolicy {
+        HfpCodecFallbackPolicy {
+            pkt_loss_ratio_threshold: 0.15,
+            window: 3,
+            cooldown: std::time::Duration::from_secs(0),
+        }
+    }
+
+    #[test]
+    fn parse_iphoneaccev_scales_battery_and_parses_dock() {
+        let state = parse_iphoneaccev("2,1,9,2,1").unwrap();
+        assert_eq!(state.battery_level_percent, Some(100));
+        assert_eq!(state.docked, Some(true));
+
+        let state = parse_iphoneaccev("1,1,0").unwrap();
+        assert_eq!(state.battery_level_percent, Some(0));
+        assert_eq!(state.docked, None);
+    }
+
+    #[test]
+    fn parse_iphoneaccev_rejects_malformed_input() {
+        assert_eq!(parse_iphoneaccev("not_a_count,1,9"), None);
+        assert_eq!(parse_iphoneaccev("2,1,9"), None);
+    }
+
+    #[test]
+    fn parse_xapl_extracts_vendor_and_product_id() {
+        let state = parse_xapl("004C-0001-0100,9").unwrap();
+        assert_eq!(state.vendor_id, Some(0x004C));
+        assert_eq!(state.product_id, Some(0x0001));
+        assert_eq!(state.battery_level_percent, None);
+    }
+
+    #[test]
+    fn parse_android_accessory_parses_battery_command() {
+        let state = parse_android_accessory("BATTERY,42").unwrap();
+        assert_eq!(state.battery_level_percent, Some(42));
+
+        assert_eq!(parse_android_accessory("DOCK,1"), None);
+    }
+
+    #[test]
+    fn parse_android_accessory_rejects_out_of_range_battery() {
+        assert_eq!(parse_android_accessory("BATTERY,100").unwrap().battery_level_percent, Some(100));
+        assert_eq!(parse_android_accessory("BATTERY,200"), None);
+    }
+
+    #[test]
+    fn parse_accessory_state_dispatches_on_prefix() {
+        assert!(parse_accessory_state("+IPHONEACCEV=1,1,5").is_some());
+        assert!(parse_accessory_state("+XAPL=004C-0001-0100,9").is_some());
+        assert!(parse_accessory_state("+ANDROID=BATTERY,50").is_some());
+        assert_eq!(parse_accessory_state("+CIEV: 1,1"), None);
+    }
+
+    #[test]
+    fn codec_fallback_ignores_inactive_or_narrowband_links() {
+        let mut controller = CodecFallbackController::new();
+        controller.set_policy(fast_policy());
+        controller.on_audio_state(BthfAudioState::Connected, addr(1));
+
+        assert_eq!(controller.on_debug_dump(/*active=*/ false, HfpCodecId::MSBC as u16, 1.0), None);
+        assert_eq!(
+            controller.on_debug_dump(/*active=*/ true, HfpCodecId::CVSD as u16, 1.0),
+            None
+        );
+    }
+
+    #[test]
+    fn codec_fallback_downgrades_after_sustained_packet_loss() {
+        let mut controller = CodecFallbackController::new();
+        controller.set_policy(fast_policy());
+        controller.on_audio_state(BthfAudioState::Connected, addr(1));
+
+        // The first dump keeps the EWMA below the threshold; the next two push it over, but the
+        // streak (2) is still short of the `window` (3), so no action yet.
+        assert_eq!(controller.on_debug_dump(true, HfpCodecId::MSBC as u16, 0.0), None);
+        assert_eq!(controller.on_debug_dump(true, HfpCodecId::MSBC as u16, 1.0), None);
+        assert_eq!(controller.on_debug_dump(true, HfpCodecId::MSBC as u16, 1.0), None);
+
+        // Once the EWMA clears the threshold for a full `window` of consecutive dumps, it downgrades.
+        let action = controller.on_debug_dump(true, HfpCodecId::MSBC as u16, 1.0);
+        assert_eq!(
+            action,
+            Some((addr(1), CodecFallbackAction::Downgrade(HfpCodecBitId::MSBC.bits())))
+        );
+
+        // The reconnect is queued only once the disconnect it asked for is actually observed.
+        assert_eq!(controller.take_pending_disconnects(), vec![addr(1)]);
+        assert_eq!(controller.take_pending_reconnects(), vec![]);
+        controller.on_audio_state(BthfAudioState::Disconnected, addr(1));
+        assert_eq!(
+            controller.take_pending_reconnects(),
+            vec![(addr(1), HfpCodecBitId::MSBC.bits())]
+        );
+    }
+
+    #[test]
+    fn codec_fallback_retries_preferred_codec_after_cooldown() {
+        let mut controller = CodecFallbackController::new();
+        controller.set_policy(fast_policy());
+        controller.on_audio_state(BthfAudioState::Connected, addr(1));
+        for _ in 0..fast_policy().window {
+            controller.on_debug_dump(true, HfpCodecId::MSBC as u16, 1.0);
+        }
+        // Drain the disconnect the downgrade queued and, as happens in production, let the real
+        // disconnect land before the reconnect: |on_audio_state| clears the per-link EWMA/streak
+        // state, but the cooldown timer must survive this since it's tracked independent of it.
+        assert_eq!(controller.take_pending_disconnects(), vec![addr(1)]);
+        controller.on_audio_state(BthfAudioState::Disconnected, addr(1));
+        assert_eq!(
+            controller.take_pending_reconnects(),
+            vec![(addr(1), HfpCodecBitId::MSBC.bits())]
+        );
+        controller.on_audio_state(BthfAudioState::Connected, addr(1));
+
+        // Cooldown is zero in `fast_policy`, so the very next dump retries the preferred codec.
+        let action = controller.on_debug_dump(true, HfpCodecId::MSBC as u16, 1.0);
+        assert_eq!(action, Some((addr(1), CodecFallbackAction::RetryPreferred)));
+        assert_eq!(controller.take_pending_disconnects(), vec![addr(1)]);
+    }
+}